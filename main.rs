@@ -1,4 +1,8 @@
-use std::{collections::HashMap, cell::RefCell};
+use std::{
+  borrow::Borrow,
+  collections::{hash_map::RandomState, HashMap},
+  hash::{BuildHasher, Hash},
+};
 use thiserror::Error;
 
 // a simple LRU cache
@@ -6,12 +10,59 @@ use thiserror::Error;
 // you can specify a max size for the cache
 // you can retrieve and store a value.
 // if the cache is full, the oldest value will be removed
+//
+// internally entries live in a slab (`Vec<Option<Node>>`) and are threaded
+// together into a doubly linked list so that moving an entry to the front on
+// a hit, and evicting the tail on a miss, are both O(1) instead of scanning
+// a Vec. `Option` lets `remove`/eviction vacate a slot for the free list to
+// hand back out later without shifting the rest of the slab.
+//
+// `capacity` is not a count of entries but a budget: each entry's "weight"
+// is whatever the cache's `Meter` says it is, and entries are evicted from
+// the tail until the running `current_size` fits back under `capacity`.
 
-type MyBytes = Vec<u8>;
-pub struct LruCache {
+/// Measures how much of the cache's capacity budget an entry consumes.
+pub trait Meter<K, V> {
+  fn measure(&self, key: &K, value: &V) -> usize;
+}
+
+/// The original behavior: every entry counts as exactly one unit, so
+/// `capacity` caps the number of entries regardless of their size.
+pub struct Count;
+
+impl<K, V> Meter<K, V> for Count {
+  fn measure(&self, _key: &K, _value: &V) -> usize {
+    1
+  }
+}
+
+/// Weighs entries by their byte length, so `capacity` becomes a memory
+/// budget instead of an entry count.
+pub struct ByteLen;
+
+impl<K, V: AsRef<[u8]>> Meter<K, V> for ByteLen {
+  fn measure(&self, _key: &K, value: &V) -> usize {
+    value.as_ref().len()
+  }
+}
+
+struct Node<K, V> {
+  key: K,
+  value: V,
+  size: usize,
+  prev: Option<usize>,
+  next: Option<usize>,
+}
+
+pub struct LruCache<K, V, S = RandomState> {
   capacity: usize,
-  map: HashMap<String, MyBytes>,
-  list: RefCell<Vec<String>>,
+  current_size: usize,
+  meter: Box<dyn Meter<K, V>>,
+  map: HashMap<K, usize, S>,
+  nodes: Vec<Option<Node<K, V>>>,
+  free: Vec<usize>,
+  head: Option<usize>,
+  tail: Option<usize>,
 }
 
 #[derive(Error, Debug)]
@@ -21,6 +72,9 @@ pub enum CacheError {
 }
 
 
+// Optional (de)serialization layer, kept for persisting/snapshotting cache
+// entries to bytes. `LruCache` itself no longer requires these: `get`/`set`
+// work with `K`/`V` directly.
 pub trait TryIntoBytes {
   fn try_into_bytes(self) -> Result<Vec<u8>, CacheError>;
 }
@@ -30,7 +84,7 @@ pub trait TryFromBytes {
     where Self: Sized;
 }
 
-// impl <T> IntoBytes for T 
+// impl <T> IntoBytes for T
 //   where T: Into<Vec<u8>>
 // {
 //   fn into_bytes(self) -> Vec<u8> {
@@ -63,43 +117,268 @@ impl TryFromBytes for i32 {
   }
 }
 
-impl LruCache {
-    pub fn new(max_size: usize) -> Self {
+impl<K: Hash + Eq + Clone, V> LruCache<K, V, RandomState> {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_meter(capacity, Count)
+    }
+
+    pub fn with_meter<M: Meter<K, V> + 'static>(capacity: usize, meter: M) -> Self {
+        Self::with_meter_and_hasher(capacity, meter, RandomState::default())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V, S: BuildHasher> LruCache<K, V, S> {
+    pub fn with_hasher(capacity: usize, hasher: S) -> Self {
+        Self::with_meter_and_hasher(capacity, Count, hasher)
+    }
+
+    fn with_meter_and_hasher<M: Meter<K, V> + 'static>(capacity: usize, meter: M, hasher: S) -> Self {
         LruCache {
-            capacity: max_size,
-            map: HashMap::new(),
-            list: RefCell::new(Vec::new()),
+            capacity,
+            current_size: 0,
+            meter: Box::new(meter),
+            map: HashMap::with_hasher(hasher),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    fn node(&self, idx: usize) -> &Node<K, V> {
+        self.nodes[idx].as_ref().expect("dangling slab index")
+    }
+
+    fn node_mut(&mut self, idx: usize) -> &mut Node<K, V> {
+        self.nodes[idx].as_mut().expect("dangling slab index")
+    }
+
+    // unlinks `idx` from wherever it currently sits in the list, patching up
+    // its neighbors' `prev`/`next` (and `head`/`tail` if it was an end).
+    fn detach(&mut self, idx: usize) {
+        let prev = self.node(idx).prev;
+        let next = self.node(idx).next;
+
+        match prev {
+            Some(p) => self.node_mut(p).next = next,
+            None => self.head = next,
         }
+        match next {
+            Some(n) => self.node_mut(n).prev = prev,
+            None => self.tail = prev,
+        }
+
+        let node = self.node_mut(idx);
+        node.prev = None;
+        node.next = None;
+    }
+
+    // splices a freshly-detached (or brand new) node in as the new head,
+    // i.e. the most-recently-used slot.
+    fn attach_front(&mut self, idx: usize) {
+        let old_head = self.head;
+
+        let node = self.node_mut(idx);
+        node.prev = None;
+        node.next = old_head;
+        if let Some(head) = old_head {
+            self.node_mut(head).prev = Some(idx);
+        }
+
+        self.head = Some(idx);
+        if self.tail.is_none() {
+            self.tail = Some(idx);
+        }
+    }
+
+    // evicts the least-recently-used node, removing it from the map and the
+    // list, adjusting `current_size`, and returns its now-vacant slab slot
+    // for reuse.
+    fn evict_tail(&mut self) -> usize {
+        let idx = self.tail.expect("evict_tail called on an empty cache");
+        self.detach(idx);
+        let node = self.nodes[idx].take().expect("dangling slab index");
+        self.map.remove(&node.key);
+        self.current_size -= node.size;
+        idx
+    }
+
+    // evicts from the tail until the weighted budget is satisfied again, not
+    // just once, since a single oversized entry can outweigh several others.
+    fn shrink_to_capacity(&mut self) {
+        while self.current_size > self.capacity && self.tail.is_some() {
+            let freed = self.evict_tail();
+            self.free.push(freed);
+        }
+    }
+
+    /// The current capacity budget, as interpreted by this cache's `Meter`.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of entries currently stored.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether the cache currently holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Changes the capacity budget, evicting least-recently-used entries
+    /// immediately if the new capacity is smaller than `current_size`.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.shrink_to_capacity();
+    }
+
+    /// Looks up `key`, marking it as the most-recently-used entry on a hit.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let idx = *self.map.get(key)?;
+        self.detach(idx);
+        self.attach_front(idx);
+        Some(&self.node(idx).value)
     }
 
-    pub fn get<V: Clone + TryFromBytes>(&self, key: &str) -> Option<V> {
-        let value = self.map.get(key)?.clone();
-        
-        self.refresh(key);
+    /// Iterates entries from least- to most-recently-used. Unlike `get`,
+    /// walking this iterator does not touch recency ordering: it walks the
+    /// linked list spine directly and never calls `detach`/`attach_front`.
+    pub fn iter(&self) -> Iter<'_, K, V, S> {
+        Iter { cache: self, next: self.tail }
+    }
+
+    /// Checks whether `key` is present, without affecting recency order.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.contains_key(key)
+    }
 
-        Some(value.clone())
+    /// Removes `key` and returns its value, if present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let idx = self.map.remove(key)?;
+        self.detach(idx);
+        let node = self.nodes[idx].take().expect("dangling slab index");
+        self.current_size -= node.size;
+        self.free.push(idx);
+        Some(node.value)
     }
 
-    fn refresh(&self, key: &str) {
-        let item_position = self.list.borrow()
-          .iter()
-          .position(|list_key| list_key == key);
+    pub fn set(&mut self, key: K, value: V) {
+        let size = self.meter.measure(&key, &value);
 
-        if let Some(item_position) = item_position {
-          let mut list = self.list.borrow_mut();
-          list.remove(item_position);
-          list.push(key.to_owned());
+        if let Some(&idx) = self.map.get(&key) {
+            self.current_size = self.current_size - self.node(idx).size + size;
+            let node = self.node_mut(idx);
+            node.value = value;
+            node.size = size;
+            self.detach(idx);
+            self.attach_front(idx);
+            self.shrink_to_capacity();
+            return;
         }
+
+        self.current_size += size;
+
+        let node = Node { key: key.clone(), value, size, prev: None, next: None };
+        let idx = if let Some(idx) = self.free.pop() {
+            self.nodes[idx] = Some(node);
+            idx
+        } else {
+            self.nodes.push(Some(node));
+            self.nodes.len() - 1
+        };
+
+        self.map.insert(key, idx);
+        self.attach_front(idx);
+        self.shrink_to_capacity();
     }
 
-    pub fn set<V: Clone + TryIntoBytes>(&mut self, key: &str, value: &V) {
-        if self.list.borrow().len() == self.capacity {
-            let oldest_key = self.list.borrow_mut().remove(0);
-            self.map.remove(&oldest_key);
+    /// Returns the value for `key`, marking it MRU, computing and inserting
+    /// it via `f` first if it isn't already present. Uses a single `Entry`
+    /// lookup for the check-and-insert, rather than a separate
+    /// `contains`/`get` followed by `set`. Returns `None` if the computed
+    /// value's weighted size alone exceeds `capacity`, in which case it gets
+    /// evicted again immediately (same "fully drains" semantics as `set`).
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> Option<&V> {
+        use std::collections::hash_map::Entry;
+
+        let idx = match self.map.entry(key.clone()) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                let value = f();
+                let size = self.meter.measure(&key, &value);
+                self.current_size += size;
+
+                let node = Node { key, value, size, prev: None, next: None };
+                let idx = if let Some(idx) = self.free.pop() {
+                    self.nodes[idx] = Some(node);
+                    idx
+                } else {
+                    self.nodes.push(Some(node));
+                    self.nodes.len() - 1
+                };
+
+                entry.insert(idx);
+                idx
+            }
+        };
+
+        self.detach(idx);
+        self.attach_front(idx);
+        self.shrink_to_capacity();
+        self.nodes[idx].as_ref().map(|node| &node.value)
+    }
+
+    /// Inserts `default` if `key` is absent, otherwise applies `f` to the
+    /// existing value in place. Re-measures the entry afterwards so weighted
+    /// `current_size` accounting stays correct even though the caller
+    /// mutated the value directly rather than going through `set`.
+    pub fn put_or_modify<F: FnMut(&mut V)>(&mut self, key: K, default: V, mut f: F) {
+        if let Some(&idx) = self.map.get(&key) {
+            f(&mut self.node_mut(idx).value);
+            let size = self.meter.measure(&key, &self.node(idx).value);
+            self.current_size = self.current_size - self.node(idx).size + size;
+            self.node_mut(idx).size = size;
+            self.detach(idx);
+            self.attach_front(idx);
+            self.shrink_to_capacity();
+            return;
         }
 
-        self.list.borrow_mut().push(key.to_owned());
-        self.map.insert(key.to_owned(), value.into_bytes());
+        self.set(key, default);
+    }
+}
+
+/// Iterator over `(&K, &V)` pairs in least- to most-recently-used order.
+/// See [`LruCache::iter`].
+pub struct Iter<'a, K, V, S> {
+    cache: &'a LruCache<K, V, S>,
+    next: Option<usize>,
+}
+
+impl<'a, K: Hash + Eq + Clone, V, S: BuildHasher> Iterator for Iter<'a, K, V, S> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let idx = self.next?;
+        let node = self.cache.node(idx);
+        // `.next` points toward the tail (LRU); starting at `tail` we have
+        // to walk back toward `head` (MRU) via `.prev` to visit LRU->MRU.
+        self.next = node.prev;
+        Some((&node.key, &node.value))
     }
 }
 
@@ -137,36 +416,147 @@ mod tests {
     #[test]
     fn it_works_for_strings() {
         let mut cache = LruCache::new(2);
-        cache.set("key1", &"value1".to_string());
-        cache.set("key2", &"value2".to_string());
+        cache.set("key1".to_string(), "value1".to_string());
+        cache.set("key2".to_string(), "value2".to_string());
         // getting key1 later should make it the most recent value
-        assert_eq!(cache.get("key2"), Some("value2".to_string()));
-        assert_eq!(cache.get("key1"), Some("value1".to_string()));
-        
+        assert_eq!(cache.get("key2"), Some(&"value2".to_string()));
+        assert_eq!(cache.get("key1"), Some(&"value1".to_string()));
+
         // setting a new value should remove the oldest value
-        cache.set("key3", &"value3".to_string());
-        assert_eq!(cache.get("key1"), Some("value1".to_string()));
-        assert_eq!(cache.get("key2"), None); 
-        assert_eq!(cache.get("key3"), Some("value3".to_string()));
+        cache.set("key3".to_string(), "value3".to_string());
+        assert_eq!(cache.get("key1"), Some(&"value1".to_string()));
+        assert_eq!(cache.get("key2"), None);
+        assert_eq!(cache.get("key3"), Some(&"value3".to_string()));
     }
 
     #[test]
     fn it_works_for_numbers() {
         let mut cache = LruCache::new(2);
-        cache.set("key1", &1);
-        cache.set("key2", &2);
+        cache.set("key1".to_string(), 1);
+        cache.set("key2".to_string(), 2);
         // getting key1 later should make it the most recent value
-        assert_eq!(cache.get("key2"), Some(2));
-        assert_eq!(cache.get("key1"), Some(1));
+        assert_eq!(cache.get("key2"), Some(&2));
+        assert_eq!(cache.get("key1"), Some(&1));
+    }
+
+    #[test]
+    fn it_looks_up_by_borrowed_key() {
+        // String keys should be gettable/removable by &str, not just &String.
+        let mut cache: LruCache<String, i32> = LruCache::new(2);
+        cache.set("key1".to_string(), 1);
+        assert!(cache.contains("key1"));
+        assert_eq!(cache.get("key1"), Some(&1));
+        assert_eq!(cache.remove("key1"), Some(1));
+        assert_eq!(cache.get("key1"), None);
+    }
+
+    #[test]
+    fn it_iterates_in_lru_to_mru_order_without_disturbing_it() {
+        let mut cache = LruCache::new(3);
+        cache.set("key1".to_string(), 1);
+        cache.set("key2".to_string(), 2);
+        cache.set("key3".to_string(), 3);
+        cache.get("key1"); // key1 becomes MRU, order is now key2, key3, key1
+
+        let seen: Vec<_> = cache.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        assert_eq!(seen, vec![
+            ("key2".to_string(), 2),
+            ("key3".to_string(), 3),
+            ("key1".to_string(), 1),
+        ]);
+
+        // iterating must not have changed the recency order itself.
+        cache.set_capacity(1);
+        assert_eq!(cache.get("key1"), Some(&1));
+    }
+
+    #[test]
+    fn it_evicts_down_to_a_shrunk_capacity() {
+        let mut cache = LruCache::new(3);
+        cache.set("key1".to_string(), 1);
+        cache.set("key2".to_string(), 2);
+        cache.set("key3".to_string(), 3);
+        assert_eq!(cache.len(), 3);
+
+        cache.set_capacity(1);
+        assert_eq!(cache.capacity(), 1);
+        assert_eq!(cache.len(), 1);
+        // key3 was the most recently used, so it's the one that survives.
+        assert_eq!(cache.get("key3"), Some(&3));
+        assert_eq!(cache.get("key1"), None);
+        assert_eq!(cache.get("key2"), None);
+    }
+
+    #[test]
+    fn it_computes_a_missing_value_only_once() {
+        let mut cache: LruCache<String, i32> = LruCache::new(2);
+        let calls = std::cell::Cell::new(0);
+
+        assert_eq!(
+            cache.get_or_insert_with("key1".to_string(), || { calls.set(calls.get() + 1); 42 }),
+            Some(&42)
+        );
+        assert_eq!(
+            cache.get_or_insert_with("key1".to_string(), || { calls.set(calls.get() + 1); 99 }),
+            Some(&42)
+        );
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn it_returns_none_when_the_computed_value_is_too_big_to_keep() {
+        let mut cache = LruCache::with_meter(10, ByteLen);
+        assert_eq!(
+            cache.get_or_insert_with("key1".to_string(), || "a value that is much bigger than the budget".to_string()),
+            None
+        );
+        assert_eq!(cache.get("key1"), None);
+    }
+
+    #[test]
+    fn it_modifies_in_place_and_keeps_weighted_size_accurate() {
+        let mut cache = LruCache::with_meter(10, ByteLen);
+        cache.put_or_modify("key1".to_string(), "ab".to_string(), |v| v.push('!'));
+        assert_eq!(cache.get("key1"), Some(&"ab".to_string()));
+
+        cache.put_or_modify("key1".to_string(), "unused default".to_string(), |v| v.push_str("cdefgh"));
+        assert_eq!(cache.get("key1"), Some(&"abcdefgh".to_string()));
+
+        // the entry is now 8 bytes; growing it past the 10 byte budget
+        // should evict it just like a `set` with the same size would.
+        cache.put_or_modify("key1".to_string(), "unused default".to_string(), |v| v.push_str("ijk"));
+        assert_eq!(cache.get("key1"), None);
+    }
+
+    #[test]
+    fn it_evicts_by_weighted_size_with_byte_len_meter() {
+        // budget of 10 bytes: "value1" (6) + "value2" (6) would overflow, so
+        // inserting key2 must evict key1 even though only 2 entries exist.
+        let mut cache = LruCache::with_meter(10, ByteLen);
+        cache.set("key1".to_string(), "value1".to_string());
+        cache.set("key2".to_string(), "value2".to_string());
+        assert_eq!(cache.get("key1"), None);
+        assert_eq!(cache.get("key2"), Some(&"value2".to_string()));
+    }
+
+    #[test]
+    fn it_fully_drains_on_a_single_oversized_insert() {
+        let mut cache = LruCache::with_meter(10, ByteLen);
+        cache.set("key1".to_string(), "value1".to_string());
+        cache.set("key2".to_string(), "a value that is much bigger than the budget".to_string());
+        assert_eq!(cache.get("key1"), None);
+        assert_eq!(cache.get("key2"), None);
     }
 
     #[test]
     fn it_works_for_structs() {
+        // values are stored directly now, so structs round-trip with no
+        // serialization step at all.
         let mut cache = LruCache::new(2);
-        cache.set("key1", &Person { name: "John".to_string(), age: 20 });
-        cache.set("key2", &Person { name: "Jane".to_string(), age: 21 });
+        cache.set("key1".to_string(), Person { name: "John".to_string(), age: 20 });
+        cache.set("key2".to_string(), Person { name: "Jane".to_string(), age: 21 });
         // getting key1 later should make it the most recent value
-        assert_eq!(cache.get("key2").try_from_byte<Person>(), Some(Person { name: "Jane".to_string(), age: 21 }));
-        assert_eq!(cache.get("key1"), Some(Person { name: "John".to_string(), age: 20 }));
+        assert_eq!(cache.get("key2"), Some(&Person { name: "Jane".to_string(), age: 21 }));
+        assert_eq!(cache.get("key1"), Some(&Person { name: "John".to_string(), age: 20 }));
     }
-}
\ No newline at end of file
+}